@@ -2,22 +2,26 @@ use std::collections::HashSet;
 use std::str::FromStr;
 use std::include_str;
 use std::error::Error;
+use std::fs;
 use std::io;
 
 #[macro_use]
 extern crate clap;
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use csv::ReaderBuilder;
 use enumset::{enum_set, EnumSet, EnumSetType};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
-use serde::Deserialize;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use tui::backend::TermionBackend;
 use tui::Terminal;
 
 use colored::*;
 
-#[derive(Debug, Deserialize, EnumSetType)]
+mod search;
+
+#[derive(Debug, Deserialize, Serialize, EnumSetType)]
 pub enum Decks {
     Codfish,
     Mackerel,
@@ -43,7 +47,7 @@ impl FromStr for Decks {
 
 // By default, struct field names are deserialized based on the position of
 // a corresponding field in the CSV data's header record.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct Building {
     name: String,
     number: String,
@@ -52,6 +56,32 @@ struct Building {
     color: String
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct InitialRow {
+    b_cards: Vec<Building>,
+    a_cards: Vec<Building>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct PlayerDraw {
+    player: u32,
+    c_cards: Vec<Building>
+}
+
+// the fully resolved inputs and dealt layout for a setup, used both for
+// `--format json` and as the on-disk shape for `--save`/`--load`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct SetupOutput {
+    deck: Decks,
+    addins: Vec<Decks>,
+    players: u32,
+    seed: u32,
+    initial_rows: Vec<InitialRow>,
+    round_3_a_cards: Vec<Building>,
+    round_4_draws: Vec<PlayerDraw>,
+    round_5_b_cards: Vec<Building>
+}
+
 fn get_size() -> Result<u16, Box<dyn Error>>{
     let stdout = io::stdout();
     let backend = TermionBackend::new(stdout);
@@ -61,6 +91,13 @@ fn get_size() -> Result<u16, Box<dyn Error>>{
 fn app() -> App<'static, 'static> {
     return app_from_crate!()
         .about("Random setup for the Nusfjord board game")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("search")
+             .about("search buildings.tsv for cards matching a filter query")
+             .arg(Arg::with_name("query")
+                  .help("filter expression, e.g. 'deck:Salmon abc:A' or 'color:Anytime OR color:Once'")
+                  .takes_value(true)
+                  .required(true)))
         .arg(Arg::with_name("players")
              .help("number of players")
              .takes_value(true)
@@ -71,7 +108,7 @@ fn app() -> App<'static, 'static> {
         .arg(Arg::from_usage("<deck> 'which deck to use'")
              .takes_value(true)
              .possible_values(&["Codfish", "Mackerel", "Herring", "Plaice", "Salmon"])
-             .required(true))
+             .required_unless("load"))
         .arg(Arg::with_name("addin")
              .short("a")
              .long("add")
@@ -88,6 +125,30 @@ fn app() -> App<'static, 'static> {
              .long("all-decks")
              .help("adds all decks (base and expansions) to initial setup")
              .conflicts_with_all(&["allbase", "addin"]))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .help("seed for reproducing a prior setup")
+             .long_help("when omitted, a random seed is drawn and printed so the run can be reproduced later")
+             .takes_value(true))
+        .arg(Arg::with_name("format")
+             .long("format")
+             .help("how to render the generated setup")
+             .takes_value(true)
+             .possible_values(&["text", "json"])
+             .default_value("text"))
+        .arg(Arg::with_name("reveal")
+             .long("reveal")
+             .help("show round 3-6 draws in their real colors instead of hiding them as spoilers"))
+        .arg(Arg::with_name("save")
+             .long("save")
+             .help("save the generated setup to a file so it can be reprinted later")
+             .takes_value(true)
+             .conflicts_with("load"))
+        .arg(Arg::with_name("load")
+             .long("load")
+             .help("re-render a setup previously written with --save, without re-shuffling")
+             .takes_value(true)
+             .conflicts_with_all(&["save", "deck", "addin", "allbase", "alldecks", "seed"]))
 }
 
 fn decks_to_use(matches: ArgMatches) -> EnumSet<Decks> {
@@ -136,10 +197,17 @@ fn colorize(text: &String, color: &String, is_spoiler:bool) -> ColoredString{
     return text.white();
 }
 
-fn print_card_row(cards: &Vec<&Building>, print_separator: bool, is_spoiler: bool) {
+// each rendered box is 24 columns wide; fall back to a conservative width
+// when the terminal size can't be determined (e.g. output is piped)
+fn cards_per_line() -> usize {
+    let width = get_size().unwrap_or(80);
+    std::cmp::max(1, (width / 24) as usize)
+}
+
+fn print_card_chunk(cards: &[&Building], offset: usize, print_separator: bool, is_spoiler: bool) {
     for i in 0..cards.len() {
         print!("/----------------------\\");
-        if i==1 && print_separator {
+        if offset+i==1 && print_separator {
             print!("|")
         }
     }
@@ -147,14 +215,14 @@ fn print_card_row(cards: &Vec<&Building>, print_separator: bool, is_spoiler: boo
     for i in 0..cards.len() {
         let cur = cards.get(i).unwrap();
         print!("| {:20} |", colorize(&cur.name, &cur.color, is_spoiler));
-        if i==1 && print_separator {
+        if offset+i==1 && print_separator {
             print!("|")
         }
     }
     println!();
     for i in 0..cards.len() {
         print!("|                      |");
-        if i==1 && print_separator {
+        if offset+i==1 && print_separator {
             print!("|")
         }
     }
@@ -162,26 +230,123 @@ fn print_card_row(cards: &Vec<&Building>, print_separator: bool, is_spoiler: boo
     for i in 0..cards.len() {
         let cur = cards.get(i).unwrap();
         print!("| {:20} |", colorize(&cur.number, &cur.color, is_spoiler));
-        if i==1 && print_separator {
+        if offset+i==1 && print_separator {
             print!("|")
         }
     }
     println!();
     for i in 0..cards.len() {
         print!("\\----------------------/");
-        if i==1 && print_separator {
+        if offset+i==1 && print_separator {
             print!("|")
         }
     }
     println!();
 }
 
+fn print_card_row(cards: &Vec<&Building>, print_separator: bool, is_spoiler: bool) {
+    let max_per_line = cards_per_line();
+    for (chunk_idx, chunk) in cards.chunks(max_per_line).enumerate() {
+        print_card_chunk(chunk, chunk_idx * max_per_line, print_separator, is_spoiler);
+    }
+}
+
+fn run_search(query: &str) {
+    let data = include_str!("buildings.tsv");
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_reader(data.as_bytes());
+    let all_buildings = rdr.deserialize::<Building>().filter_map(Result::ok).collect::<Vec<Building>>();
+
+    let predicate = search::parse_query(query).unwrap_or_else(|e| {
+        eprintln!("invalid search query: {}", e);
+        std::process::exit(1);
+    });
+
+    for b in all_buildings.iter().filter(|b| predicate(b)) {
+        print_card_row(&vec![b], false, false);
+    }
+}
+
+fn render_text(output: &SetupOutput, is_spoiler: bool) {
+    println!("Seed: {}", output.seed);
+    for row in &output.initial_rows {
+        let cards: Vec<&Building> = row.b_cards.iter().chain(row.a_cards.iter()).collect();
+        print_card_row(&cards, true, false);
+    }
+    if !output.round_3_a_cards.is_empty() {
+        println!("********* ROUND 3 CARDS *********");
+        print_card_row(&output.round_3_a_cards.iter().collect(), false, is_spoiler);
+    }
+    println!("******** ROUND 4 CARDS ********");
+    for draw in &output.round_4_draws {
+        println!("Doing Player {}", draw.player);
+        print_card_row(&draw.c_cards.iter().collect(), false, is_spoiler);
+    }
+    if !output.round_5_b_cards.is_empty() {
+        println!("********* ROUND 5 CARDS *********");
+        print_card_row(&output.round_5_b_cards.iter().collect(), false, is_spoiler);
+    }
+}
+
+fn render(output: &SetupOutput, format: &str, is_spoiler: bool) {
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(output).unwrap());
+    } else {
+        render_text(output, is_spoiler);
+    }
+}
+
+fn load_setup(path: &str) -> SetupOutput {
+    let data = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    serde_json::from_str(&data).unwrap_or_else(|e| {
+        eprintln!("could not parse {}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+fn save_setup(path: &str, output: &SetupOutput) {
+    let data = serde_json::to_string_pretty(output).unwrap();
+    fs::write(path, data).unwrap_or_else(|e| {
+        eprintln!("could not write {}: {}", path, e);
+        std::process::exit(1);
+    });
+}
+
 fn main() {
     let matches =
         app().get_matches();
 
+    if let Some(search_matches) = matches.subcommand_matches("search") {
+        let query = search_matches.value_of("query").unwrap();
+        run_search(query);
+        return;
+    }
+
+    let format = value_t!(matches, "format", String).unwrap_or_else(|e| e.exit());
+    let is_spoiler = !matches.is_present("reveal");
+
+    if matches.is_present("load") {
+        if matches.occurrences_of("players") > 0 {
+            eprintln!("--players is ignored with --load; the player count from the saved setup is used instead");
+        }
+        let path = value_t!(matches, "load", String).unwrap_or_else(|e| e.exit());
+        let output = load_setup(&path);
+        render(&output, &format, is_spoiler);
+        return;
+    }
+    let save_path = value_t!(matches, "save", String).ok();
+
     let players = value_t!(matches, "players", u32).unwrap_or_else(|e| e.exit());
     let main_deck = value_t!(matches, "deck", Decks).unwrap_or_else(|e| e.exit());
+    let seed = if matches.is_present("seed") {
+        value_t!(matches, "seed", u32).unwrap_or_else(|e| e.exit())
+    } else {
+        rand::thread_rng().gen()
+    };
     let decks_to_use = decks_to_use(matches);
 
     let data = include_str!("buildings.tsv");
@@ -198,7 +363,7 @@ fn main() {
         .filter(|b| decks_to_use.contains(b.deck) && b.abc=="B")
         .collect::<Vec<&Building>>();
 
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed as u64);
     setup_a_buildings.shuffle(&mut rng);
     setup_b_buildings.shuffle(&mut rng);
 
@@ -207,26 +372,26 @@ fn main() {
     let mut a_iter = setup_a_buildings.iter();
     let mut b_iter = setup_b_buildings.iter();
     /* 3 rows each with 2 B cards and 3 A cards */
-    let mut cur_row: Vec<&Building> = Vec::new();
+    let mut initial_rows: Vec<InitialRow> = Vec::new();
     for __ in 0..3 {
+        let mut row_b_cards: Vec<Building> = Vec::new();
+        let mut row_a_cards: Vec<Building> = Vec::new();
         for _ in 0..2 {
             let b = b_iter.next().unwrap();
             if b.deck == main_deck {
                 dealt_main_deck_cards.insert(&b.number);
             }
-            cur_row.push(b);
+            row_b_cards.push(b.clone());
         }
         for _ in 0..3 {
             let b = a_iter.next().unwrap();
             if b.deck == main_deck {
                 dealt_main_deck_cards.insert(&b.number);
             }
-            cur_row.push(b);
+            row_a_cards.push(b.clone());
         }
-        print_card_row(&cur_row, true, false);
-        cur_row.clear();
+        initial_rows.push(InitialRow { b_cards: row_b_cards, a_cards: row_a_cards });
     }
-    cur_row.clear();
     let mut ingame_a_buildings = all_buildings.iter()
         .filter(|b| b.deck==main_deck && b.abc=="A" && !dealt_main_deck_cards.contains(&b.number))
         .collect::<Vec<&Building>>();
@@ -253,37 +418,42 @@ fn main() {
         _ => 0
     };
 
-    if round_3_a_cards > 0 {
-        println!("********* ROUND 3 CARDS *********");
-        let mut iter2 = ingame_a_buildings.iter();
-        for _ in 0..round_3_a_cards {
-            cur_row.push(iter2.next().unwrap());
-        }
-        print_card_row(&cur_row, false, true);
-        cur_row.clear();
+    let mut round_3_cards: Vec<Building> = Vec::new();
+    let mut iter2 = ingame_a_buildings.iter();
+    for _ in 0..round_3_a_cards {
+        round_3_cards.push(iter2.next().unwrap().clone());
     }
 
-    println!("******** ROUND 4 CARDS ********");
+    let mut round_4_draws: Vec<PlayerDraw> = Vec::new();
     let mut iter3 = ingame_c_buildings.iter();
     for p in 0..players {
-        println!("Doing Player {}", p);
+        let mut c_cards: Vec<Building> = Vec::new();
         for _ in 0..round_4_c_cards {
-            cur_row.push(iter3.next().unwrap());
+            c_cards.push(iter3.next().unwrap().clone());
         }
-        print_card_row(&cur_row, false, true);
-        cur_row.clear();
+        round_4_draws.push(PlayerDraw { player: p, c_cards });
     }
-    if round_5_b_cards > 0 {
-        println!("********* ROUND 5 CARDS *********");
-        let mut iter4 = ingame_b_buildings.iter();
-        for _ in 0..round_5_b_cards {
-            cur_row.push(iter4.next().unwrap());
-        }
-        print_card_row(&cur_row, false, true);
-        cur_row.clear();
+
+    let mut round_5_cards: Vec<Building> = Vec::new();
+    let mut iter4 = ingame_b_buildings.iter();
+    for _ in 0..round_5_b_cards {
+        round_5_cards.push(iter4.next().unwrap().clone());
     }
 
+    let output = SetupOutput {
+        deck: main_deck,
+        addins: decks_to_use.iter().filter(|&d| d != main_deck).collect(),
+        players,
+        seed,
+        initial_rows,
+        round_3_a_cards: round_3_cards,
+        round_4_draws,
+        round_5_b_cards: round_5_cards
+    };
+
+    render(&output, &format, is_spoiler);
 
-    // TODO truncate
-    // println!("Size is {}", get_size().unwrap());
+    if let Some(path) = save_path {
+        save_setup(&path, &output);
+    }
 }
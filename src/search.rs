@@ -0,0 +1,73 @@
+use std::str::FromStr;
+
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, multispace1};
+use nom::bytes::complete::take_while1;
+use nom::multi::separated_list1;
+use nom::sequence::{separated_pair, tuple};
+use nom::IResult;
+
+use crate::{Building, Decks};
+
+fn is_term_char(c: char) -> bool {
+    !c.is_whitespace() && c != ':'
+}
+
+// a single `field:value` predicate, e.g. `deck:Salmon` or `name:harbor`
+fn term(input: &str) -> IResult<&str, (&str, &str)> {
+    separated_pair(take_while1(is_term_char), char(':'), take_while1(is_term_char))(input)
+}
+
+// space-separated terms are implicitly ANDed together
+fn and_group(input: &str) -> IResult<&str, Vec<(&str, &str)>> {
+    separated_list1(multispace1, term)(input)
+}
+
+// `OR` between groups of terms
+fn query(input: &str) -> IResult<&str, Vec<Vec<(&str, &str)>>> {
+    separated_list1(tuple((multispace1, tag("OR"), multispace1)), and_group)(input)
+}
+
+fn term_predicate(field: &str, value: &str) -> Result<Box<dyn Fn(&Building) -> bool>, String> {
+    match field {
+        "deck" => {
+            let wanted = Decks::from_str(value).ok();
+            Ok(Box::new(move |b: &Building| wanted.map_or(false, |d| b.deck == d)))
+        }
+        "abc" => {
+            let wanted = value.to_string();
+            Ok(Box::new(move |b: &Building| b.abc.eq_ignore_ascii_case(&wanted)))
+        }
+        "color" => {
+            let wanted = value.to_lowercase();
+            Ok(Box::new(move |b: &Building| b.color.to_lowercase().contains(&wanted)))
+        }
+        "number" => {
+            let wanted = value.to_string();
+            Ok(Box::new(move |b: &Building| b.number == wanted))
+        }
+        "name" => {
+            let wanted = value.to_lowercase();
+            Ok(Box::new(move |b: &Building| b.name.to_lowercase().contains(&wanted)))
+        }
+        _ => Err(format!("unknown field: {}", field))
+    }
+}
+
+/// Parses a query like `deck:Salmon abc:A OR color:Anytime` into a predicate
+/// over `Building`, combining space-separated terms with AND and `OR` groups
+/// with OR.
+pub fn parse_query(input: &str) -> Result<Box<dyn Fn(&Building) -> bool>, String> {
+    let (remaining, groups) = query(input.trim()).map_err(|e| e.to_string())?;
+    if !remaining.is_empty() {
+        return Err(format!("unexpected input: {}", remaining));
+    }
+
+    let predicates: Vec<Vec<Box<dyn Fn(&Building) -> bool>>> = groups.into_iter()
+        .map(|group| group.into_iter().map(|(f, v)| term_predicate(f, v)).collect::<Result<Vec<_>, _>>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Box::new(move |b: &Building| {
+        predicates.iter().any(|group| group.iter().all(|p| p(b)))
+    }))
+}